@@ -1,168 +1,556 @@
 use bimap::BiMap;
-use std::{cell::RefCell, cmp::Reverse, collections::HashMap, rc::Rc};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap},
+    hash::Hash,
+    io::{self, Cursor, Read, Write},
+};
 use bitvec::prelude::*;
 
+/// A Huffman tree stored as a flat arena instead of `Rc<RefCell<_>>` nodes.
+/// Leaves occupy indices `0..leaf_count`, internal nodes occupy
+/// `leaf_count..`, and `root` is the index of the top node. This makes the
+/// tree plain data: no interior mutability, no refcounting, and it is
+/// trivially `Clone`.
 #[derive(Debug, Clone)]
-enum TreeNode {
-    InternalNode {
-        left: TreeNodeRef,
-        right: TreeNodeRef,
-        count: usize,
-    },
-    LeafNode {
-        char: char,
-        count: usize,
-    },
-}
-type TreeNodeRef = Rc<RefCell<TreeNode>>;
-type HuffmanTable = BiMap<char, BitVec>;
-
-impl TreeNode {
+struct HuffmanTree<S> {
+    nodes: Vec<Node<S>>,
+    root: u32,
+}
+
+#[derive(Debug, Clone)]
+enum Node<S> {
+    Internal { left: u32, right: u32, count: usize },
+    Leaf { symbol: S, count: usize },
+}
+
+impl<S> Node<S> {
     fn count(&self) -> usize {
         match self {
-            Self::InternalNode { count, .. } | Self::LeafNode { count, .. } => *count,
+            Self::Internal { count, .. } | Self::Leaf { count, .. } => *count,
         }
     }
+}
 
-    fn new_leaf(char: char, count: usize) -> TreeNodeRef {
-        Rc::new(RefCell::new(Self::LeafNode { char, count }))
-    }
-
-    fn new_internal(left: TreeNodeRef, right: TreeNodeRef) -> TreeNodeRef {
-        let mut count = 0;
-        count += left.borrow().count();
-        count += right.borrow().count();
-        Rc::new(RefCell::new(Self::InternalNode { left, right, count }))
+impl<S> HuffmanTree<S> {
+    fn node(&self, index: u32) -> &Node<S> {
+        &self.nodes[index as usize]
     }
 }
 
+type HuffmanTable<S> = BiMap<S, BitVec>;
+
 /// CALCULATING TABLE
 
+fn count_symbols<S: Hash + Eq + Copy>(symbols: impl Iterator<Item = S>) -> HashMap<S, usize> {
+    let mut counts = HashMap::new();
+    for symbol in symbols {
+        *counts.entry(symbol).or_insert(0) += 1;
+    }
+    counts
+}
+
 fn count_chars(string: &str) -> HashMap<char, usize> {
-    let mut chars = HashMap::new();
-    for char in string.chars() {
-        *chars.entry(char).or_insert(0) += 1;
+    count_symbols(string.chars())
+}
+
+/// Byte streams are common enough, and small enough in alphabet, that a
+/// `[usize; 256]` array is both faster to build and faster to look up than
+/// going through a `HashMap<u8, usize>`.
+fn count_bytes(data: &[u8]) -> [usize; 256] {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
     }
-    chars
+    counts
+}
+
+fn counted_bytes(data: &[u8]) -> impl Iterator<Item = (u8, usize)> {
+    count_bytes(data)
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, count)| count > 0)
+        .map(|(byte, count)| (byte as u8, count))
+}
+
+// Orders arena indices for the construction heap. Only `count` and
+// `min_symbol` (the smallest symbol reachable from that node) are compared,
+// so merging never needs to index back into the arena.
+struct HeapNode<S> {
+    count: usize,
+    min_symbol: S,
+    index: u32,
 }
 
-fn initialize_nodes(counts: HashMap<char, usize>) -> Vec<TreeNodeRef> {
-    let mut vec = vec![];
-    for (char, count) in counts.iter() {
-        vec.push((*char, *count));
+impl<S: Eq> PartialEq for HeapNode<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.count == other.count && self.min_symbol == other.min_symbol
+    }
+}
+impl<S: Eq> Eq for HeapNode<S> {}
+impl<S: Ord + Copy> PartialOrd for HeapNode<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<S: Ord + Copy> Ord for HeapNode<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.count, self.min_symbol).cmp(&(other.count, other.min_symbol))
     }
-    vec.sort_by_key(|(char, count)| (Reverse(*count), *char as u64));
-    vec.iter()
-        .map(|(char, count)| TreeNode::new_leaf(*char, *count))
-        .collect()
 }
 
-fn construct_tree(mut nodes: Vec<TreeNodeRef>) -> TreeNodeRef {
-    while nodes.len() > 1 {
-        let x = TreeNode::new_internal(nodes.pop().unwrap(), nodes.pop().unwrap());
-        nodes.push(x);
-        nodes.sort_by_key(|node| Reverse(node.borrow().count()));
+fn construct_tree<S: Ord + Copy>(counts: impl IntoIterator<Item = (S, usize)>) -> HuffmanTree<S> {
+    let mut leaves: Vec<(S, usize)> = counts.into_iter().collect();
+    leaves.sort_by_key(|(symbol, count)| (Reverse(*count), *symbol));
+
+    let mut nodes: Vec<Node<S>> = leaves
+        .into_iter()
+        .map(|(symbol, count)| Node::Leaf { symbol, count })
+        .collect();
+
+    let mut heap: BinaryHeap<Reverse<HeapNode<S>>> = nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let (count, min_symbol) = match *node {
+                Node::Leaf { symbol, count } => (count, symbol),
+                Node::Internal { .. } => unreachable!("initial nodes are always leaves"),
+            };
+            Reverse(HeapNode { count, min_symbol, index: index as u32 })
+        })
+        .collect();
+
+    while heap.len() > 1 {
+        let Reverse(a) = heap.pop().unwrap();
+        let Reverse(b) = heap.pop().unwrap();
+        let min_symbol = a.min_symbol.min(b.min_symbol);
+        let count = nodes[a.index as usize].count() + nodes[b.index as usize].count();
+        let index = nodes.len() as u32;
+        nodes.push(Node::Internal { left: a.index, right: b.index, count });
+        heap.push(Reverse(HeapNode { count, min_symbol, index }));
+    }
+
+    let root = heap.pop().unwrap().0.index;
+
+    // a single-symbol alphabet never enters the merge loop above, so `root`
+    // is still a bare leaf with no branch to hang a code off of. Wrap it in
+    // a synthetic internal node whose two sides both lead back to it: the
+    // leaf gets a 1-bit code instead of the unusable 0-bit code a bare leaf
+    // root would otherwise produce (which, in the decode tables built from
+    // this tree, would consume zero bits per symbol and loop forever).
+    if nodes.len() == 1 {
+        let count = nodes[root as usize].count();
+        let wrapped = nodes.len() as u32;
+        nodes.push(Node::Internal { left: root, right: root, count });
+        return HuffmanTree { nodes, root: wrapped };
     }
-    nodes.pop().unwrap()
-    //Rc::try_unwrap(nodes.pop().expect("array is not empty"))
-    //    .expect("there are no other references to the node")
-    //    .into_inner()
+
+    HuffmanTree { nodes, root }
 }
 
-fn calculate_encodings(tree: Rc<RefCell<TreeNode>>) -> HuffmanTable {
+fn calculate_encodings<S: Hash + Eq + Copy>(tree: &HuffmanTree<S>) -> HuffmanTable<S> {
     let mut encodings = BiMap::new();
-    let mut stack = vec![(tree, BitVec::new())];
+    let mut stack = vec![(tree.root, BitVec::new())];
 
-    while !stack.is_empty() {
-        let (node, index): (Rc<RefCell<TreeNode>>, BitVec) = stack.pop().unwrap();
-        match *node.borrow() {
-            TreeNode::LeafNode { char, .. } => {
-                encodings.insert(char, index.clone());
+    while let Some((index, bits)) = stack.pop() {
+        match tree.node(index) {
+            Node::Leaf { symbol, .. } => {
+                encodings.insert(*symbol, bits);
             }
-            TreeNode::InternalNode {
-                ref left,
-                ref right,
-                ..
-            } => {
-                let mut a = index.clone();
+            Node::Internal { left, right, .. } => {
+                let mut a = bits.clone();
                 a.push(true);
-                stack.push((Rc::clone(right), a));
-                let mut b = index.clone();
+                stack.push((*right, a));
+                let mut b = bits;
                 b.push(false);
-                stack.push((Rc::clone(left), b));
+                stack.push((*left, b));
             }
-        };
+        }
     }
 
     encodings
 }
 
-fn calculate_huffman_tree(str: &str) -> Rc<RefCell<TreeNode>> {
-    let counts = count_chars(str);
-    let nodes = initialize_nodes(counts);
-    construct_tree(nodes)
+fn calculate_huffman_table<S: Hash + Eq + Ord + Copy>(
+    counts: impl IntoIterator<Item = (S, usize)>,
+) -> HuffmanTable<S> {
+    calculate_encodings(&construct_tree(counts))
+}
+
+/// CANONICAL CODES
+///
+/// A canonical code only needs one bit-length per symbol to be fully
+/// reconstructed: symbols are sorted by `(length, symbol)` and assigned
+/// consecutive codes, incrementing by one and left-shifting whenever the
+/// length grows. This lets the header store `Vec<(S, u8)>` instead of a
+/// full tree or a table of `(S, BitVec)` pairs.
+
+fn code_lengths<S: Hash + Eq + Copy>(table: &HuffmanTable<S>) -> Vec<(S, u8)> {
+    table
+        .iter()
+        .map(|(symbol, bits)| (*symbol, bits.len() as u8))
+        .collect()
 }
 
-fn calculate_huffman_table(str: &str) -> HuffmanTable {
-    let counts = count_chars(str);
-    let nodes = initialize_nodes(counts);
-    let tree = construct_tree(nodes);
-    calculate_encodings(tree)
+fn build_canonical_codes<S: Hash + Eq + Ord + Copy>(mut lengths: Vec<(S, u8)>) -> HuffmanTable<S> {
+    lengths.sort_by_key(|(symbol, len)| (*len, *symbol));
+
+    let mut table = BiMap::new();
+    let mut code: u32 = 0;
+    let mut prev_len = 0u8;
+    for (symbol, len) in lengths {
+        if prev_len != 0 {
+            code <<= len - prev_len;
+        }
+        let mut bits = BitVec::new();
+        for i in (0..len).rev() {
+            bits.push((code >> i) & 1 == 1);
+        }
+        table.insert(symbol, bits);
+        code += 1;
+        prev_len = len;
+    }
+    table
+}
+
+/// Builds a Huffman table and returns it alongside the length-only header
+/// needed to reconstruct it with [`rebuild_canonical_table`].
+fn calculate_canonical_table<S: Hash + Eq + Ord + Copy>(
+    counts: impl IntoIterator<Item = (S, usize)>,
+) -> (HuffmanTable<S>, Vec<(S, u8)>) {
+    let table = calculate_huffman_table(counts);
+    let lengths = code_lengths(&table);
+    let canonical = build_canonical_codes(lengths.clone());
+    (canonical, lengths)
+}
+
+/// Rebuilds a canonical table from a length-only header, with no tree or
+/// code data required.
+fn rebuild_canonical_table<S: Hash + Eq + Ord + Copy>(lengths: &[(S, u8)]) -> HuffmanTable<S> {
+    build_canonical_codes(lengths.to_vec())
 }
 
 /// DISPLAYING VISUALLY
 
-fn print_encodings(encodings: &HuffmanTable) -> () {
+fn print_encodings<S: Hash + Eq + std::fmt::Display>(encodings: &HuffmanTable<S>) -> () {
     print!("{{\n");
-    for (char, index) in encodings.iter() {
-        print!("'{char}' > {index}\n")
+    for (symbol, index) in encodings.iter() {
+        print!("'{symbol}' > {index}\n")
     }
     print!("}}\n");
 }
 
 /// ENCODING/DECODING DATA
 
-fn huffman_encode(str: &str, table: HuffmanTable) -> BitVec {
+fn huffman_encode<S: Hash + Eq + Copy>(symbols: impl IntoIterator<Item = S>, table: &HuffmanTable<S>) -> BitVec {
     let mut vec = BitVec::new();
-    for char in str.chars() {
-        let index = table.get_by_left(&char).expect("char should be in the table");
-        vec.append(&mut index.clone());
+    for symbol in symbols {
+        let index = table.get_by_left(&symbol).expect("symbol should be in the table");
+        vec.extend_from_bitslice(index);
     }
     vec
 }
 
-fn huffman_decode(mut bits: BitVec, tree: Rc<RefCell<TreeNode>>) -> String {
-    let mut result = String::new();
-    let mut node = Rc::clone(&tree);
+fn huffman_decode<S: Copy>(mut bits: BitVec, tree: &HuffmanTree<S>) -> Vec<S> {
+    let mut result = vec![];
+    let mut index = tree.root;
     bits.reverse(); // reversed because popping is faster
     while !bits.is_empty() {
         let bit = bits.pop().unwrap();
-        node = if bit {
-            match *node.borrow() {
-                TreeNode::InternalNode { ref right, .. } => Rc::clone(right),
-                TreeNode::LeafNode { .. } => unreachable!(),
-            }
-        } else {
-            match *node.borrow() {
-                TreeNode::InternalNode { ref left, .. } => Rc::clone(left),
-                TreeNode::LeafNode { .. } => unreachable!(),
+        index = match tree.node(index) {
+            Node::Internal { left, right, .. } => if bit { *right } else { *left },
+            Node::Leaf { .. } => unreachable!(),
+        };
+
+        if let Node::Leaf { symbol, .. } = tree.node(index) {
+            result.push(*symbol);
+            index = tree.root;
+        }
+    }
+    result
+}
+
+/// FAST TABLE-DRIVEN DECODING
+///
+/// `huffman_decode` walks the tree one bit at a time. `compile_decode_tree`
+/// instead precomputes, for every possible `k`-bit prefix, either the symbol
+/// it resolves to (plus how many of those bits were actually consumed) or a
+/// nested sub-table to continue into when the bits run out before a leaf is
+/// reached. Decoding then becomes a sequence of `k`-bit table lookups
+/// instead of single-bit tree walks.
+enum DecodeEntry<S> {
+    Done(S, u8),
+    Continue(Box<[DecodeEntry<S>]>),
+}
+
+/// A compiled decode table together with the prefix width `k` bits it was
+/// built for. Bundling the two keeps decode calls from being handed a
+/// different `k` than the table was compiled with, which would silently
+/// misdecode instead of failing.
+struct DecodeTable<S> {
+    k: u8,
+    entries: Box<[DecodeEntry<S>]>,
+}
+
+fn decode_entry_for_prefix<S: Copy>(tree: &HuffmanTree<S>, start: u32, prefix: usize, k: u8) -> DecodeEntry<S> {
+    let mut current = start;
+    for i in 0..k {
+        if let Node::Leaf { symbol, .. } = tree.node(current) {
+            return DecodeEntry::Done(*symbol, i);
+        }
+        let bit = (prefix >> (k - 1 - i)) & 1 == 1;
+        current = match tree.node(current) {
+            Node::Internal { left, right, .. } => if bit { *right } else { *left },
+            Node::Leaf { .. } => unreachable!(),
+        };
+    }
+    match tree.node(current) {
+        Node::Leaf { symbol, .. } => DecodeEntry::Done(*symbol, k),
+        Node::Internal { .. } => DecodeEntry::Continue(compile_decode_entries(tree, current, k)),
+    }
+}
+
+fn compile_decode_entries<S: Copy>(tree: &HuffmanTree<S>, start: u32, k: u8) -> Box<[DecodeEntry<S>]> {
+    (0..1usize << k)
+        .map(|prefix| decode_entry_for_prefix(tree, start, prefix, k))
+        .collect()
+}
+
+fn compile_decode_tree<S: Copy>(tree: &HuffmanTree<S>, k: u8) -> DecodeTable<S> {
+    DecodeTable { k, entries: compile_decode_entries(tree, tree.root, k) }
+}
+
+fn read_prefix(bits: &BitSlice, pos: usize, k: u8) -> usize {
+    let mut prefix = 0usize;
+    for i in 0..k as usize {
+        let bit = bits.get(pos + i).as_deref().copied().unwrap_or(false);
+        prefix = (prefix << 1) | bit as usize;
+    }
+    prefix
+}
+
+fn huffman_decode_fast<S: Copy>(bits: &BitSlice, table: &DecodeTable<S>) -> Vec<S> {
+    let mut result = vec![];
+    let mut pos = 0;
+    while pos < bits.len() {
+        let mut current = &table.entries;
+        loop {
+            let prefix = read_prefix(bits, pos, table.k);
+            match &current[prefix] {
+                DecodeEntry::Done(symbol, consumed) => {
+                    result.push(*symbol);
+                    pos += *consumed as usize;
+                    break;
+                }
+                DecodeEntry::Continue(sub_table) => {
+                    pos += table.k as usize;
+                    current = sub_table;
+                }
             }
+        }
+    }
+    result
+}
+
+/// CONTAINER FORMAT
+///
+/// A self-contained on-disk format so encoded output is usable on its own
+/// instead of a `BitVec` that only means something alongside an
+/// in-memory table. The header holds everything needed to decode: a magic
+/// tag, the canonical code-lengths table, and the original symbol count.
+/// Storing that count (rather than relying on the payload running out of
+/// bits) is also what makes single-symbol inputs round-trip: such an
+/// input gets a 1-bit code, and the header tells the decoder how many
+/// times to read it.
+const MAGIC: &[u8; 4] = b"HUF1";
+
+fn container_table_and_lengths(counts: Vec<(u8, usize)>) -> (HuffmanTable<u8>, Vec<(u8, u8)>) {
+    match counts[..] {
+        // no symbols at all: an empty table round-trips an empty input
+        // without a tree to build it from.
+        [] => (HuffmanTable::new(), vec![]),
+        [(symbol, _)] => {
+            let lengths = vec![(symbol, 1u8)];
+            let table = build_canonical_codes(lengths.clone());
+            (table, lengths)
+        }
+        _ => calculate_canonical_table(counts),
+    }
+}
+
+/// Builds a decode tree directly from a symbol -> code table, instead of
+/// from symbol counts. Used to get back a [`HuffmanTree`] (and from there a
+/// [`DecodeTable`]) after rebuilding a table from a container header, where
+/// there are no counts left to reconstruct a weighted tree from.
+fn tree_from_table<S: Hash + Eq + Copy>(table: &HuffmanTable<S>) -> HuffmanTree<S> {
+    if let [(symbol, _)] = table.iter().collect::<Vec<_>>()[..] {
+        // a lone symbol still needs a branch to hang its 1-bit code off of;
+        // both sides lead to the only leaf there is.
+        return HuffmanTree {
+            nodes: vec![Node::Leaf { symbol: *symbol, count: 0 }, Node::Internal { left: 0, right: 0, count: 0 }],
+            root: 1,
         };
+    }
+
+    enum Building<S> {
+        Internal { left: Option<u32>, right: Option<u32> },
+        Leaf(S),
+    }
 
-        let x = node.borrow();
-        match *x {
-            TreeNode::LeafNode { char, .. } => {
-                result.push(char);
-                drop(x);
-                node = Rc::clone(&tree);
+    let mut building = vec![Building::Internal { left: None, right: None }];
+    let root = 0u32;
+
+    for (symbol, bits) in table.iter() {
+        let mut current = root;
+        let last = bits.len() - 1;
+        for (i, bit) in bits.iter().enumerate() {
+            let existing = match &building[current as usize] {
+                Building::Internal { left, right } => if *bit { *right } else { *left },
+                Building::Leaf(_) => unreachable!("prefix code should never branch through a leaf"),
+            };
+            current = match existing {
+                Some(index) => index,
+                None => {
+                    let index = building.len() as u32;
+                    building.push(if i == last {
+                        Building::Leaf(*symbol)
+                    } else {
+                        Building::Internal { left: None, right: None }
+                    });
+                    match &mut building[current as usize] {
+                        Building::Internal { left, right } => {
+                            if *bit { *right = Some(index) } else { *left = Some(index) }
+                        }
+                        Building::Leaf(_) => unreachable!(),
+                    }
+                    index
+                }
+            };
+        }
+    }
+
+    let nodes = building
+        .into_iter()
+        .map(|node| match node {
+            Building::Leaf(symbol) => Node::Leaf { symbol, count: 0 },
+            Building::Internal { left, right } => Node::Internal {
+                left: left.expect("canonical codes form a complete binary tree"),
+                right: right.expect("canonical codes form a complete binary tree"),
+                count: 0,
             },
-            _ => (),
-        };
-    };
+        })
+        .collect();
+
+    HuffmanTree { nodes, root }
+}
+
+fn pack_bits(bits: &BitSlice) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, bit)| if *bit { byte | (0x80 >> i) } else { byte })
+        })
+        .collect()
+}
+
+fn bits_from_bytes(bytes: &[u8]) -> BitVec {
+    let mut bits = BitVec::with_capacity(bytes.len() * 8);
+    for &byte in bytes {
+        for i in 0..8 {
+            bits.push((byte >> (7 - i)) & 1 == 1);
+        }
+    }
+    bits
+}
+
+fn huffman_decode_n<S: Copy>(bits: &BitSlice, table: &DecodeTable<S>, symbol_count: usize) -> Vec<S> {
+    let mut result = Vec::with_capacity(symbol_count);
+    let mut pos = 0;
+    while result.len() < symbol_count {
+        let mut current = &table.entries;
+        loop {
+            let prefix = read_prefix(bits, pos, table.k);
+            match &current[prefix] {
+                DecodeEntry::Done(symbol, consumed) => {
+                    result.push(*symbol);
+                    pos += *consumed as usize;
+                    break;
+                }
+                DecodeEntry::Continue(sub_table) => {
+                    pos += table.k as usize;
+                    current = sub_table;
+                }
+            }
+        }
+    }
     result
 }
 
-// FIXME: strings with only one unique character break, as they are encoded as []
+fn encode_to_writer<W: Write>(input: &[u8], w: &mut W) -> io::Result<()> {
+    let (table, lengths) = container_table_and_lengths(counted_bytes(input).collect());
+    let bits = huffman_encode(input.iter().copied(), &table);
+
+    w.write_all(MAGIC)?;
+    w.write_all(&(lengths.len() as u16).to_le_bytes())?;
+    w.write_all(&(input.len() as u64).to_le_bytes())?;
+    for &(symbol, len) in &lengths {
+        w.write_all(&[symbol, len])?;
+    }
+    w.write_all(&pack_bits(&bits))
+}
+
+fn decode_from_reader<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a huffman-testing container"));
+    }
+
+    let mut symbol_count_bytes = [0u8; 2];
+    r.read_exact(&mut symbol_count_bytes)?;
+    let symbol_count = u16::from_le_bytes(symbol_count_bytes) as usize;
+
+    let mut original_len_bytes = [0u8; 8];
+    r.read_exact(&mut original_len_bytes)?;
+    let original_len = u64::from_le_bytes(original_len_bytes) as usize;
+
+    let mut lengths = Vec::with_capacity(symbol_count);
+    for _ in 0..symbol_count {
+        let mut entry = [0u8; 2];
+        r.read_exact(&mut entry)?;
+        lengths.push((entry[0], entry[1]));
+    }
+
+    let mut payload = vec![];
+    r.read_to_end(&mut payload)?;
+    let bits = bits_from_bytes(&payload);
+
+    // an empty table has no tree to build a decode table from; it can only
+    // mean the original input was empty, so decode straight to that.
+    if symbol_count == 0 {
+        return if original_len == 0 {
+            Ok(vec![])
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "symbol table is empty but original length is not"))
+        };
+    }
+
+    // `original_len` is attacker/corruption-controlled; bound it by the
+    // payload actually read so a truncated or malformed header can't trigger
+    // an oversized allocation in `huffman_decode_n` before decoding notices
+    // anything is wrong. Every symbol costs at least one bit, so the payload
+    // can never encode more symbols than it has bits.
+    if original_len > bits.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "declared length exceeds payload size"));
+    }
+
+    let table = rebuild_canonical_table(&lengths);
+    let tree = tree_from_table(&table);
+    let decode_table = compile_decode_tree(&tree, 8);
+    Ok(huffman_decode_n(&bits, &decode_table, original_len))
+}
 
 fn main() {
     //let input = "qwertyuiopasdfghjklzxcvbnm1234567890-=[]#';/.,\\";
@@ -175,18 +563,224 @@ Morbi vulputate hendrerit lobortis. Curabitur suscipit mauris ex. Ut mollis augu
 ";
     println!("{input}");
 
-    let tree = calculate_huffman_tree(input);
-    let table = calculate_encodings(tree.clone());
+    // the byte-oriented path is the primary use case: it works on any
+    // `&[u8]`, not just valid UTF-8 text
+    let data = input.as_bytes();
+
+    let tree = construct_tree(counted_bytes(data));
+    let table = calculate_encodings(&tree);
     print_encodings(&table);
-    let x = huffman_encode(input, table.clone());
+    let x = huffman_encode(data.iter().copied(), &table);
     println!("{x}");
-    let y = huffman_decode(x.clone(), tree);
-    assert!(y == input);
-
-    let char_size = std::mem::size_of::<char>() * 8;
-    let original_size = input.len() * char_size;
-    // assumes optimal packing of huffman table
-    let huffman_size = 
-        x.len() + table.into_iter().map(|(_, bits)| char_size + bits.len()).sum::<usize>();
-    println!("before: {original_size}, after: {huffman_size}, ratio: {:.2}x original size", (huffman_size as f64) / (original_size as f64))
+    let y = huffman_decode(x.clone(), &tree);
+    assert!(y.as_slice() == data);
+
+    let decode_table = compile_decode_tree(&tree, 8);
+    let y_fast = huffman_decode_fast(&x, &decode_table);
+    assert!(y_fast.as_slice() == data);
+
+    // the same pipeline still works symbol-by-symbol over `char`, not just `u8`
+    let char_tree = construct_tree(count_chars(input));
+    let char_table = calculate_encodings(&char_tree);
+    let char_encoded = huffman_encode(input.chars(), &char_table);
+    let char_decoded: String = huffman_decode(char_encoded, &char_tree).into_iter().collect();
+    assert!(char_decoded == input);
+
+    // canonical round-trip: only the per-symbol code lengths are kept, the
+    // full table is rebuilt from those on the other side
+    let (canonical_table, lengths) = calculate_canonical_table(counted_bytes(data));
+    let rebuilt_table = rebuild_canonical_table(&lengths);
+    assert!(canonical_table == rebuilt_table);
+    let canonical_encoded = huffman_encode(data.iter().copied(), &canonical_table);
+
+    let original_size = data.len() * 8;
+    // a u8 symbol plus a length byte per table entry, instead of a symbol
+    // plus a full code
+    let header_size = lengths.len() * (8 + 8);
+    let huffman_size = canonical_encoded.len() + header_size;
+    println!("before: {original_size}, after: {huffman_size}, ratio: {:.2}x original size", (huffman_size as f64) / (original_size as f64));
+
+    // the container format is fully self-describing: decoding only needs
+    // the bytes written by `encode_to_writer`, no tree or table passed
+    // alongside
+    let mut container = Cursor::new(vec![]);
+    encode_to_writer(data, &mut container).expect("writing to a Vec cannot fail");
+    println!("container size: {} bytes", container.get_ref().len());
+
+    container.set_position(0);
+    let roundtripped = decode_from_reader(&mut container).expect("container was just written by us");
+    assert!(roundtripped == data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_table_round_trips_lengths() {
+        let counts = vec![('a', 5usize), ('b', 2), ('c', 1), ('d', 1)];
+        let (_, lengths) = calculate_canonical_table(counts);
+        let rebuilt = rebuild_canonical_table(&lengths);
+
+        let mut rebuilt_lengths = code_lengths(&rebuilt);
+        rebuilt_lengths.sort_by_key(|(symbol, _)| *symbol);
+        let mut original_lengths = lengths;
+        original_lengths.sort_by_key(|(symbol, _)| *symbol);
+
+        assert_eq!(rebuilt_lengths, original_lengths);
+    }
+
+    #[test]
+    fn canonical_codes_are_prefix_free() {
+        // two symbols sharing the same length, one with a shorter one
+        let lengths = vec![('a', 1u8), ('b', 2), ('c', 2)];
+        let table = build_canonical_codes(lengths);
+
+        let codes: Vec<BitVec> = table.iter().map(|(_, bits)| bits.clone()).collect();
+        for (i, a) in codes.iter().enumerate() {
+            for b in &codes[i + 1..] {
+                let shorter = a.len().min(b.len());
+                assert_ne!(&a[..shorter], &b[..shorter], "{a:?} is a prefix of {b:?} or vice versa");
+            }
+        }
+    }
+
+    #[test]
+    fn decode_fast_handles_single_symbol_alphabet() {
+        // regression test: a single-symbol tree used to leave `root` as a
+        // bare leaf, so the compiled decode table returned a 0-bit code and
+        // `huffman_decode_fast` looped forever without advancing `pos`.
+        let tree = construct_tree(vec![(b'a', 3usize)]);
+        let table = compile_decode_tree(&tree, 8);
+        let bits = bitvec![0, 0, 0];
+        assert_eq!(huffman_decode_fast(&bits, &table), vec![b'a', b'a', b'a']);
+    }
+
+    #[test]
+    fn decode_fast_round_trips_small_multi_symbol_input() {
+        let input = b"abracadabra";
+        let tree = construct_tree(counted_bytes(input));
+        let table = calculate_encodings(&tree);
+        let bits = huffman_encode(input.iter().copied(), &table);
+
+        let decode_table = compile_decode_tree(&tree, 4);
+        assert_eq!(huffman_decode_fast(&bits, &decode_table), input.to_vec());
+    }
+
+    #[test]
+    fn construct_tree_builds_one_internal_node_per_merge() {
+        // n leaves always merge down to n - 1 internal nodes, regardless of
+        // tie-breaking order, since each merge removes one heap entry.
+        let counts = vec![('a', 5usize), ('b', 2), ('c', 2), ('d', 1), ('e', 1)];
+        let tree = construct_tree(counts);
+        assert_eq!(tree.nodes.len(), 5 + 4);
+    }
+
+    #[test]
+    fn construct_tree_breaks_count_ties_by_symbol() {
+        // equal counts must still produce a deterministic tree, so the same
+        // input always yields the same codes.
+        let a = construct_tree(vec![('x', 1usize), ('y', 1), ('z', 1)]);
+        let b = construct_tree(vec![('z', 1usize), ('y', 1), ('x', 1)]);
+        let encodings_a = calculate_encodings(&a);
+        let encodings_b = calculate_encodings(&b);
+        assert_eq!(encodings_a, encodings_b);
+    }
+
+    #[test]
+    fn pipeline_is_generic_over_char_symbols() {
+        let input = "mississippi";
+        let table = calculate_huffman_table(count_chars(input));
+        let bits = huffman_encode(input.chars(), &table);
+        let tree = construct_tree(count_chars(input));
+        assert_eq!(huffman_decode(bits, &tree), input.chars().collect::<Vec<char>>());
+    }
+
+    #[test]
+    fn counted_bytes_matches_count_symbols() {
+        let input = b"mississippi";
+        let mut from_fast_path: Vec<(u8, usize)> = counted_bytes(input).collect();
+        from_fast_path.sort();
+
+        let mut from_generic: Vec<(u8, usize)> = count_symbols(input.iter().copied()).into_iter().collect();
+        from_generic.sort();
+
+        assert_eq!(from_fast_path, from_generic);
+    }
+
+    #[test]
+    fn tree_node_indexes_into_the_arena() {
+        let tree = construct_tree(vec![('a', 3usize), ('b', 1)]);
+        // two leaves merge into exactly one internal node, at the index
+        // appended right after them
+        match tree.node(tree.root) {
+            Node::Internal { left, right, .. } => {
+                assert!(matches!(tree.node(*left), Node::Leaf { .. }));
+                assert!(matches!(tree.node(*right), Node::Leaf { .. }));
+            }
+            Node::Leaf { .. } => panic!("two distinct symbols must produce an internal root"),
+        }
+    }
+
+    #[test]
+    fn tree_is_cheaply_cloneable() {
+        let tree = construct_tree(vec![('a', 3usize), ('b', 1)]);
+        let cloned = tree.clone();
+        assert_eq!(cloned.root, tree.root);
+        assert_eq!(cloned.nodes.len(), tree.nodes.len());
+    }
+
+    fn round_trip_container(input: &[u8]) -> Vec<u8> {
+        let mut container = Cursor::new(vec![]);
+        encode_to_writer(input, &mut container).expect("writing to a Vec cannot fail");
+        container.set_position(0);
+        decode_from_reader(&mut container).expect("container was just written by us")
+    }
+
+    #[test]
+    fn container_round_trips_empty_input() {
+        assert_eq!(round_trip_container(b""), b"");
+    }
+
+    #[test]
+    fn container_round_trips_single_symbol_input() {
+        assert_eq!(round_trip_container(b"aaaaa"), b"aaaaa");
+    }
+
+    #[test]
+    fn container_round_trips_small_multi_symbol_input() {
+        assert_eq!(round_trip_container(b"abracadabra"), b"abracadabra");
+    }
+
+    #[test]
+    fn container_rejects_truncated_header() {
+        let mut container = Cursor::new(vec![]);
+        encode_to_writer(b"abracadabra", &mut container).expect("writing to a Vec cannot fail");
+
+        let mut truncated = container.into_inner();
+        truncated.truncate(6); // keeps magic and symbol count, cuts off the whole original-length field
+        let mut reader = Cursor::new(truncated);
+
+        assert!(decode_from_reader(&mut reader).is_err());
+    }
+
+    #[test]
+    fn container_rejects_bad_magic() {
+        let mut reader = Cursor::new(b"NOPE".to_vec());
+        assert!(decode_from_reader(&mut reader).is_err());
+    }
+
+    #[test]
+    fn container_rejects_declared_length_exceeding_payload() {
+        let mut container = Cursor::new(vec![]);
+        encode_to_writer(b"abracadabra", &mut container).expect("writing to a Vec cannot fail");
+
+        let mut bytes = container.into_inner();
+        // original_len is stored at offset 6 as a little-endian u64; inflate
+        // it far past what the payload actually has bits for
+        bytes[6..14].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut reader = Cursor::new(bytes);
+
+        assert!(decode_from_reader(&mut reader).is_err());
+    }
 }